@@ -1,10 +1,14 @@
 use chrono::{Local, Utc};
 use dotenvy::dotenv;
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use sled::Db;
-use std::{sync::Arc, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 use teloxide::{
-    dispatching::{Dispatcher, UpdateFilterExt},
+    dispatching::{
+        dialogue::{self, Storage},
+        Dispatcher, UpdateFilterExt,
+    },
     dptree,
     prelude::*,
     types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
@@ -14,44 +18,325 @@ use teloxide::{
    DOMAIN (Entities + pure logic)
    ========================================================= */
 
-#[derive(Serialize, Deserialize)]
-enum InputMode {
-    None,
-    AddNote,
+/// Conversation state for a single chat, driven by the teloxide dialogue FSM.
+#[derive(Clone, Default, Serialize, Deserialize)]
+enum BotState {
+    #[default]
+    MainMenu,
+    AddingNote,
+    AwaitingReminderReply,
+    ForcedApply {
+        deadline: i64,
+    },
 }
 
-impl Default for InputMode {
-    fn default() -> Self {
-        Self::None
-    }
+type BotDialogue = Dialogue<BotState, DialogueStorage>;
+
+const DEFAULT_REMINDER_SECS: u64 = 900;
+
+/// A user-defined quest macro, created via `/newquest`.
+#[derive(Clone, Serialize, Deserialize)]
+struct Quest {
+    name: String,
+    xp: u32,
+    gold: u32,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+// `serde(default)` falls back to `Default for User` for any field missing
+// from a stored record, so loading a profile saved under an older schema
+// (before this field existed) fills it in instead of failing to
+// deserialize the whole user and resetting their progress.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 struct User {
     level: u32,
     xp: u32,
     gold: u32,
     log: Vec<String>,
     notes: Vec<String>,
-    input: InputMode,
 
-    awaiting_ping: bool,
     last_ping_ts: i64,
+    reminder_secs: u64,
+    next_ping_ts: i64,
+
+    tz_offset_minutes: i32,
+    quiet_start: u8,
+    quiet_end: u8,
+
+    custom_quests: Vec<Quest>,
+
+    /// Telegram first name, cached at `/start` time for the leaderboard —
+    /// `all()` only has this stored struct, not live profile data.
+    first_name: String,
+}
+
+impl Default for User {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            xp: 0,
+            gold: 0,
+            log: Vec::new(),
+            notes: Vec::new(),
+            last_ping_ts: 0,
+            reminder_secs: DEFAULT_REMINDER_SECS,
+            next_ping_ts: 0,
+            tz_offset_minutes: 0,
+            quiet_start: 0,
+            quiet_end: 0,
+            custom_quests: Vec::new(),
+            first_name: String::new(),
+        }
+    }
 }
 
 fn xp_to_next(level: u32) -> u32 {
     level * 100
 }
 
+/// Parses durations like `"2h30m"` or `"45m"` into a second count, scanning
+/// left to right and multiplying each accumulated digit run by the unit
+/// that follows it (`d`=86400, `h`=3600, `m`=60, `s`=1). A bare number with
+/// no unit is treated as minutes.
+fn parse_duration_secs(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("укажи длительность, например 1h30m".into());
+    }
+
+    let mut total: u64 = 0;
+    let mut current: u64 = 0;
+    let mut has_unit = false;
+
+    for ch in input.chars() {
+        if let Some(d) = ch.to_digit(10) {
+            current = current.saturating_mul(10).saturating_add(d as u64);
+        } else {
+            let secs_per_unit = match ch {
+                'd' => 86_400,
+                'h' => 3_600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(format!("неизвестная единица измерения: '{ch}'")),
+            };
+            total = total.saturating_add(current.saturating_mul(secs_per_unit));
+            current = 0;
+            has_unit = true;
+        }
+    }
+
+    if !has_unit {
+        total = current.saturating_mul(60);
+    } else if current != 0 {
+        return Err("после числа должна идти единица измерения (d/h/m/s)".into());
+    }
+
+    if total == 0 {
+        return Err("длительность должна быть больше нуля".into());
+    }
+
+    Ok(total)
+}
+
+/// Parses a timezone offset in minutes, e.g. `"+180"` or `"-300"`.
+fn parse_tz_offset(input: &str) -> Result<i32, String> {
+    input
+        .trim()
+        .parse()
+        .map_err(|_| "укажи смещение в минутах, например +180".to_string())
+}
+
+/// Parses a quiet-hours window as two space-separated hours `"23 7"`.
+fn parse_quiet_hours(input: &str) -> Result<(u8, u8), String> {
+    let mut parts = input.trim().split_whitespace();
+    let (Some(start), Some(end)) = (parts.next(), parts.next()) else {
+        return Err("укажи часы начала и конца, например 23 7".to_string());
+    };
+
+    let parse_hour = |s: &str| -> Result<u8, String> {
+        s.parse::<u8>()
+            .ok()
+            .filter(|h| *h < 24)
+            .ok_or_else(|| format!("час должен быть от 0 до 23: '{s}'"))
+    };
+
+    Ok((parse_hour(start)?, parse_hour(end)?))
+}
+
+/// Upper bound on a custom quest's xp/gold — built-in quests top out at
+/// 50 xp / 1 gold, so this leaves plenty of headroom while keeping
+/// `complete_quest`'s arithmetic well away from `u32::MAX`.
+const MAX_QUEST_REWARD: u32 = 1_000;
+
+/// Parses a custom quest macro in `Name|xp|gold` form, e.g. `"Workout|40|2"`.
+fn parse_quest(input: &str) -> Result<Quest, String> {
+    let mut parts = input.trim().split('|');
+    let (Some(name), Some(xp), Some(gold)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err("формат: Название|xp|золото, например Workout|40|2".to_string());
+    };
+
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("название квеста не может быть пустым".to_string());
+    }
+
+    let xp: u32 = xp
+        .trim()
+        .parse()
+        .map_err(|_| format!("xp должно быть числом: '{xp}'"))?;
+    let gold: u32 = gold
+        .trim()
+        .parse()
+        .map_err(|_| format!("золото должно быть числом: '{gold}'"))?;
+
+    if xp > MAX_QUEST_REWARD || gold > MAX_QUEST_REWARD {
+        return Err(format!(
+            "xp и золото должны быть не больше {MAX_QUEST_REWARD}"
+        ));
+    }
+
+    Ok(Quest {
+        name: name.to_string(),
+        xp,
+        gold,
+    })
+}
+
+/// The user's current local hour, given their UTC-offset in minutes.
+fn local_hour(now_ts: i64, tz_offset_minutes: i32) -> u8 {
+    let local_ts = now_ts + tz_offset_minutes as i64 * 60;
+    local_ts.div_euclid(3600).rem_euclid(24) as u8
+}
+
+/// Whether `hour` falls inside `[quiet_start, quiet_end)`, correctly
+/// handling windows that wrap past midnight (e.g. 23 → 7). Equal bounds
+/// mean quiet hours are disabled.
+fn is_quiet_hour(hour: u8, quiet_start: u8, quiet_end: u8) -> bool {
+    if quiet_start == quiet_end {
+        return false;
+    }
+    if quiet_start < quiet_end {
+        hour >= quiet_start && hour < quiet_end
+    } else {
+        hour >= quiet_start || hour < quiet_end
+    }
+}
+
 /* =========================================================
-   REPOSITORY (DB access)
+   DIALOGUE STORAGE (FSM persistence, backed by sled)
    ========================================================= */
 
-struct UserRepository {
+/// Stores `BotState` per chat in its own sled database (`sled_dialogue_db`), independent of
+/// whichever `UserStore` backend is handling user records.
+struct DialogueStorage {
     db: Db,
 }
 
-impl UserRepository {
+impl DialogueStorage {
+    fn new() -> Arc<Self> {
+        let db = sled::open("sled_dialogue_db").expect("failed to open dialogue db");
+        Arc::new(Self { db })
+    }
+
+    fn key(chat_id: ChatId) -> String {
+        format!("dialogue:{}", chat_id.0)
+    }
+}
+
+impl Storage<BotState> for DialogueStorage {
+    type Error = Infallible;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let _ = self.db.remove(Self::key(chat_id));
+            Ok(())
+        })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: BotState,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let bytes = serde_json::to_vec(&dialogue).expect("BotState always serializes");
+            let _ = self.db.insert(Self::key(chat_id), bytes);
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<BotState>, Self::Error>> {
+        Box::pin(async move {
+            let state = self
+                .db
+                .get(Self::key(chat_id))
+                .ok()
+                .flatten()
+                .and_then(|v| serde_json::from_slice(&v).ok());
+            Ok(state)
+        })
+    }
+}
+
+async fn dialogue_state(dialogue: &BotDialogue) -> BotState {
+    dialogue.get().await.ok().flatten().unwrap_or_default()
+}
+
+/* =========================================================
+   REPOSITORY (storage backends)
+   ========================================================= */
+
+/// Persistence for `User` records, independent of the concrete database.
+/// `UserService` talks only to this trait, so the backend can be swapped
+/// via the `USER_STORE` env var without touching business logic.
+trait UserStore: Send + Sync {
+    fn load(&self, user_id: u64) -> User;
+    fn save(&self, user_id: u64, user: &User);
+    fn all(&self) -> Vec<(u64, User)>;
+
+    /// Total number of registered users. The default counts `all()`;
+    /// backends with a real `COUNT(*)` query should override this instead.
+    fn count(&self) -> usize {
+        self.all().len()
+    }
+
+    /// Top `limit` users ordered by level then XP. The default sorts
+    /// `all()` in memory; backends with a real ranking query (SQL
+    /// `ORDER BY`) should override this instead.
+    fn top_n(&self, limit: usize) -> Vec<(u64, User)> {
+        let mut users = self.all();
+        users.sort_by(|a, b| {
+            b.1.level
+                .cmp(&a.1.level)
+                .then(b.1.xp.cmp(&a.1.xp))
+                .then(a.0.cmp(&b.0))
+        });
+        users.truncate(limit);
+        users
+    }
+
+    /// `user_id`'s 1-based rank among all users (by level then XP), paired
+    /// with their record, or `None` if they don't exist. The default sorts
+    /// `all()` in memory; backends with a real ranking query (SQL
+    /// `COUNT(*) WHERE ...`) should override this instead.
+    fn rank_of(&self, user_id: u64) -> Option<(usize, User)> {
+        let ranked = UserService::rank_users(self.all());
+        let rank = ranked.iter().find(|r| r.user_id == user_id)?.rank;
+        Some((rank, self.load(user_id)))
+    }
+}
+
+struct SledUserStore {
+    db: Db,
+}
+
+impl SledUserStore {
     fn new() -> Self {
         Self {
             db: sled::open("sled_db").expect("failed to open sled db"),
@@ -61,7 +346,9 @@ impl UserRepository {
     fn key(user_id: u64) -> String {
         format!("user:{user_id}")
     }
+}
 
+impl UserStore for SledUserStore {
     fn load(&self, user_id: u64) -> User {
         self.db
             .get(Self::key(user_id))
@@ -93,19 +380,192 @@ impl UserRepository {
     }
 }
 
+/// `rusqlite`-backed store. `level`/`xp`/`gold` are kept as real columns so
+/// `top_n` can run `ORDER BY ... LIMIT n` in the database instead of
+/// deserializing every row; the rest of `User` rides along as a JSON blob.
+struct SqliteUserStore {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+}
+
+impl SqliteUserStore {
+    fn new() -> Self {
+        let conn = rusqlite::Connection::open("users.db").expect("failed to open sqlite db");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS users (
+                user_id INTEGER PRIMARY KEY,
+                level   INTEGER NOT NULL,
+                xp      INTEGER NOT NULL,
+                gold    INTEGER NOT NULL,
+                data    TEXT NOT NULL
+            )",
+            [],
+        )
+        .expect("failed to create users table");
+
+        Self {
+            conn: std::sync::Mutex::new(conn),
+        }
+    }
+
+    fn row_to_user((id, data): (i64, String)) -> Option<(u64, User)> {
+        serde_json::from_str(&data)
+            .ok()
+            .map(|user| (id as u64, user))
+    }
+}
+
+impl UserStore for SqliteUserStore {
+    fn load(&self, user_id: u64) -> User {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT data FROM users WHERE user_id = ?1",
+            [user_id as i64],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+    }
+
+    fn save(&self, user_id: u64, user: &User) {
+        let data = serde_json::to_string(user).unwrap();
+        let conn = self.conn.lock().unwrap();
+        let _ = conn.execute(
+            "INSERT INTO users (user_id, level, xp, gold, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(user_id) DO UPDATE SET
+                level = excluded.level,
+                xp = excluded.xp,
+                gold = excluded.gold,
+                data = excluded.data",
+            rusqlite::params![user_id as i64, user.level, user.xp, user.gold, data],
+        );
+    }
+
+    fn all(&self) -> Vec<(u64, User)> {
+        let conn = self.conn.lock().unwrap();
+        let Ok(mut stmt) = conn.prepare("SELECT user_id, data FROM users") else {
+            return Vec::new();
+        };
+        let Ok(rows) = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))) else {
+            return Vec::new();
+        };
+        rows.filter_map(|r| r.ok())
+            .filter_map(Self::row_to_user)
+            .collect()
+    }
+
+    fn top_n(&self, limit: usize) -> Vec<(u64, User)> {
+        let conn = self.conn.lock().unwrap();
+        let Ok(mut stmt) =
+            conn.prepare(
+                "SELECT user_id, data FROM users ORDER BY level DESC, xp DESC, user_id ASC LIMIT ?1",
+            )
+        else {
+            return Vec::new();
+        };
+        let Ok(rows) = stmt.query_map(rusqlite::params![limit as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        }) else {
+            return Vec::new();
+        };
+        rows.filter_map(|r| r.ok())
+            .filter_map(Self::row_to_user)
+            .collect()
+    }
+
+    fn count(&self) -> usize {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM users", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .ok()
+        .map_or(0, |c| c as usize)
+    }
+
+    fn rank_of(&self, user_id: u64) -> Option<(usize, User)> {
+        let conn = self.conn.lock().unwrap();
+        let data: String = conn
+            .query_row(
+                "SELECT data FROM users WHERE user_id = ?1",
+                [user_id as i64],
+                |row| row.get(0),
+            )
+            .ok()?;
+        let user: User = serde_json::from_str(&data).ok()?;
+
+        // Same ordering as `rank_users`: level desc, then xp desc, then
+        // user_id asc as a deterministic tiebreaker — so two backends
+        // never disagree on the rank of identical stored data.
+        let better: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM users WHERE
+                    level > ?1
+                    OR (level = ?1 AND xp > ?2)
+                    OR (level = ?1 AND xp = ?2 AND user_id < ?3)",
+                rusqlite::params![user.level, user.xp, user_id as i64],
+                |row| row.get(0),
+            )
+            .unwrap_or(0);
+
+        Some((better as usize + 1, user))
+    }
+}
+
+/// Picks the storage backend from the `USER_STORE` env var (`"sqlite"` or
+/// `"sled"`, defaulting to `"sled"`).
+fn build_user_store() -> Arc<dyn UserStore> {
+    match std::env::var("USER_STORE").as_deref() {
+        Ok("sqlite") => Arc::new(SqliteUserStore::new()),
+        _ => Arc::new(SledUserStore::new()),
+    }
+}
+
 /* =========================================================
    SERVICE (Business logic)
    ========================================================= */
 
+/// One leaderboard row, produced by `UserService::rank_users`.
+struct Ranked {
+    user_id: u64,
+    rank: usize,
+    first_name: String,
+    level: u32,
+    xp: u32,
+}
+
 struct UserService {
-    repo: Arc<UserRepository>,
+    repo: Arc<dyn UserStore>,
 }
 
 impl UserService {
-    fn new(repo: Arc<UserRepository>) -> Self {
+    fn new(repo: Arc<dyn UserStore>) -> Self {
         Self { repo }
     }
 
+    /// Orders users by level then XP and assigns 1-based ranks. Pure and
+    /// independent of storage, so it can be exercised with plain fixtures.
+    fn rank_users(mut users: Vec<(u64, User)>) -> Vec<Ranked> {
+        users.sort_by(|a, b| {
+            b.1.level
+                .cmp(&a.1.level)
+                .then(b.1.xp.cmp(&a.1.xp))
+                .then(a.0.cmp(&b.0))
+        });
+
+        users
+            .into_iter()
+            .enumerate()
+            .map(|(i, (user_id, user))| Ranked {
+                user_id,
+                rank: i + 1,
+                first_name: user.first_name,
+                level: user.level,
+                xp: user.xp,
+            })
+            .collect()
+    }
+
     fn load(&self, user_id: u64) -> User {
         self.repo.load(user_id)
     }
@@ -130,8 +590,8 @@ impl UserService {
         xp: u32,
         gold: u32,
     ) -> Option<u32> {
-        user.xp += xp;
-        user.gold += gold;
+        user.xp = user.xp.saturating_add(xp);
+        user.gold = user.gold.saturating_add(gold);
 
         let mut level_up = None;
         while user.xp >= xp_to_next(user.level) {
@@ -173,11 +633,12 @@ fn main_menu() -> InlineKeyboardMarkup {
             InlineKeyboardButton::callback("📖 Журнал", "log"),
             InlineKeyboardButton::callback("🗒 Заметки", "notes"),
         ],
+        vec![InlineKeyboardButton::callback("🏆 Рейтинг", "top")],
     ])
 }
 
-fn quest_menu() -> InlineKeyboardMarkup {
-    InlineKeyboardMarkup::new(vec![
+fn quest_menu(custom_quests: &[Quest]) -> InlineKeyboardMarkup {
+    let mut rows = vec![
         vec![
             InlineKeyboardButton::callback("💼 Отклик", "q_apply"),
             InlineKeyboardButton::callback("🧠 Учёба", "q_study"),
@@ -187,8 +648,17 @@ fn quest_menu() -> InlineKeyboardMarkup {
             InlineKeyboardButton::callback("✉️ Рекрутер", "q_recruiter"),
         ],
         vec![InlineKeyboardButton::callback("🛠️ Проект", "q_project")],
-        vec![InlineKeyboardButton::callback("⬅️ Назад", "back")],
-    ])
+    ];
+
+    for (i, quest) in custom_quests.iter().enumerate() {
+        rows.push(vec![InlineKeyboardButton::callback(
+            format!("⭐ {}", quest.name),
+            format!("cq:{i}"),
+        )]);
+    }
+
+    rows.push(vec![InlineKeyboardButton::callback("⬅️ Назад", "back")]);
+    InlineKeyboardMarkup::new(rows)
 }
 
 fn notes_menu() -> InlineKeyboardMarkup {
@@ -221,33 +691,54 @@ async fn main() {
     pretty_env_logger::init();
 
     let bot = Bot::from_env();
-    let repo = Arc::new(UserRepository::new());
-    let service = Arc::new(UserService::new(repo.clone()));
+    let service = Arc::new(UserService::new(build_user_store()));
+    let storage = DialogueStorage::new();
 
     /* ===== BACKGROUND REMINDER ===== */
     {
         let bot = bot.clone();
         let service = service.clone();
+        let storage = storage.clone();
 
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(900));
+            let mut interval = tokio::time::interval(Duration::from_secs(30));
             loop {
                 interval.tick().await;
+                let now = Utc::now().timestamp();
 
                 for (user_id, mut user) in service.repo.all() {
-                    if user.awaiting_ping {
+                    if now < user.next_ping_ts {
+                        continue;
+                    }
+
+                    let hour = local_hour(now, user.tz_offset_minutes);
+                    if is_quiet_hour(hour, user.quiet_start, user.quiet_end) {
+                        continue;
+                    }
+
+                    let chat_id = ChatId(user_id as i64);
+                    let dialogue = BotDialogue::new(storage.clone(), chat_id);
+                    let state = dialogue_state(&dialogue).await;
+
+                    // Don't clobber a user who's mid some other flow (writing
+                    // a note, racing a forced-apply deadline) — leave their
+                    // state and next_ping_ts alone so we pick them back up
+                    // once they return to the main menu.
+                    if matches!(state, BotState::AddingNote | BotState::ForcedApply { .. }) {
+                        continue;
+                    }
+
+                    if let BotState::AwaitingReminderReply = state {
                         UserService::punish(&mut user, 20);
                     }
 
-                    user.awaiting_ping = true;
-                    user.last_ping_ts = Utc::now().timestamp();
+                    user.last_ping_ts = now;
+                    user.next_ping_ts = now + user.reminder_secs as i64;
                     service.save(user_id, &user);
+                    let _ = dialogue.update(BotState::AwaitingReminderReply).await;
 
                     let _ = bot
-                        .send_message(
-                            ChatId(user_id as i64),
-                            "⏰ Что ты сделал для поиска работы?",
-                        )
+                        .send_message(chat_id, "⏰ Что ты сделал для поиска работы?")
                         .reply_markup(reminder_menu())
                         .await;
                 }
@@ -255,19 +746,25 @@ async fn main() {
         });
     }
 
-    let handler = dptree::entry()
+    let handler = dialogue::enter::<Update, DialogueStorage, BotState, _>()
         // /start
         .branch(
             Update::filter_message()
                 .filter(|m: Message| m.text() == Some("/start"))
                 .endpoint({
                     let service = service.clone();
-                    move |bot: Bot, msg: Message| {
+                    move |bot: Bot, msg: Message, dialogue: BotDialogue| {
                         let service = service.clone();
                         async move {
                             let Some(from) = msg.from() else { return Ok(()); };
-                            let user = service.load(from.id.0);
+                            let mut user = service.load(from.id.0);
+                            if user.next_ping_ts == 0 {
+                                user.next_ping_ts =
+                                    Utc::now().timestamp() + user.reminder_secs as i64;
+                            }
+                            user.first_name = from.first_name.clone();
                             service.save(from.id.0, &user);
+                            let _ = dialogue.update(BotState::MainMenu).await;
 
                             bot.send_message(msg.chat.id, "🎮 Поиск работы — MMORPG")
                                 .reply_markup(main_menu())
@@ -277,24 +774,176 @@ async fn main() {
                     }
                 }),
         )
+        // /remind <duration>
+        .branch(
+            Update::filter_message()
+                .filter(|m: Message| m.text().is_some_and(|t| t.starts_with("/remind")))
+                .endpoint({
+                    let service = service.clone();
+                    move |bot: Bot, msg: Message| {
+                        let service = service.clone();
+                        async move {
+                            let Some(from) = msg.from() else { return Ok(()); };
+                            let arg = msg.text().unwrap().trim_start_matches("/remind").trim();
+
+                            match parse_duration_secs(arg) {
+                                Ok(secs) => {
+                                    let mut user = service.load(from.id.0);
+                                    user.reminder_secs = secs;
+                                    user.next_ping_ts = Utc::now().timestamp() + secs as i64;
+                                    service.save(from.id.0, &user);
+
+                                    bot.send_message(
+                                        msg.chat.id,
+                                        format!("⏰ Буду напоминать каждые {secs} сек."),
+                                    )
+                                    .await?;
+                                }
+                                Err(e) => {
+                                    bot.send_message(msg.chat.id, format!("⚠️ {e}")).await?;
+                                }
+                            }
+                            Ok(())
+                        }
+                    }
+                }),
+        )
+        // /tz <offset_minutes>
+        .branch(
+            Update::filter_message()
+                .filter(|m: Message| m.text().is_some_and(|t| t.starts_with("/tz")))
+                .endpoint({
+                    let service = service.clone();
+                    move |bot: Bot, msg: Message| {
+                        let service = service.clone();
+                        async move {
+                            let Some(from) = msg.from() else { return Ok(()); };
+                            let arg = msg.text().unwrap().trim_start_matches("/tz").trim();
+
+                            match parse_tz_offset(arg) {
+                                Ok(offset) => {
+                                    let mut user = service.load(from.id.0);
+                                    user.tz_offset_minutes = offset;
+                                    service.save(from.id.0, &user);
+
+                                    bot.send_message(
+                                        msg.chat.id,
+                                        format!("🌍 Часовой пояс: смещение {offset} мин."),
+                                    )
+                                    .await?;
+                                }
+                                Err(e) => {
+                                    bot.send_message(msg.chat.id, format!("⚠️ {e}")).await?;
+                                }
+                            }
+                            Ok(())
+                        }
+                    }
+                }),
+        )
+        // /quiet <start_hour> <end_hour>
+        .branch(
+            Update::filter_message()
+                .filter(|m: Message| m.text().is_some_and(|t| t.starts_with("/quiet")))
+                .endpoint({
+                    let service = service.clone();
+                    move |bot: Bot, msg: Message| {
+                        let service = service.clone();
+                        async move {
+                            let Some(from) = msg.from() else { return Ok(()); };
+                            let arg = msg.text().unwrap().trim_start_matches("/quiet").trim();
+
+                            match parse_quiet_hours(arg) {
+                                Ok((start, end)) => {
+                                    let mut user = service.load(from.id.0);
+                                    user.quiet_start = start;
+                                    user.quiet_end = end;
+                                    service.save(from.id.0, &user);
+
+                                    bot.send_message(
+                                        msg.chat.id,
+                                        format!("🌙 Тихие часы: с {start} до {end}"),
+                                    )
+                                    .await?;
+                                }
+                                Err(e) => {
+                                    bot.send_message(msg.chat.id, format!("⚠️ {e}")).await?;
+                                }
+                            }
+                            Ok(())
+                        }
+                    }
+                }),
+        )
+        // /newquest <Name|xp|gold>
+        .branch(
+            Update::filter_message()
+                .filter(|m: Message| m.text().is_some_and(|t| t.starts_with("/newquest")))
+                .endpoint({
+                    let service = service.clone();
+                    move |bot: Bot, msg: Message| {
+                        let service = service.clone();
+                        async move {
+                            let Some(from) = msg.from() else { return Ok(()); };
+                            let arg = msg.text().unwrap().trim_start_matches("/newquest").trim();
+
+                            match parse_quest(arg) {
+                                Ok(quest) => {
+                                    let mut user = service.load(from.id.0);
+                                    let name = quest.name.clone();
+                                    user.custom_quests.push(quest);
+                                    service.save(from.id.0, &user);
+
+                                    bot.send_message(
+                                        msg.chat.id,
+                                        format!("⭐ Квест «{name}» добавлен"),
+                                    )
+                                    .await?;
+                                }
+                                Err(e) => {
+                                    bot.send_message(msg.chat.id, format!("⚠️ {e}")).await?;
+                                }
+                            }
+                            Ok(())
+                        }
+                    }
+                }),
+        )
+        // /top
+        .branch(
+            Update::filter_message()
+                .filter(|m: Message| m.text() == Some("/top"))
+                .endpoint({
+                    let service = service.clone();
+                    move |bot: Bot, msg: Message| {
+                        let service = service.clone();
+                        async move {
+                            let Some(from) = msg.from() else { return Ok(()); };
+                            let text = leaderboard_text(service.repo.as_ref(), from.id.0);
+                            bot.send_message(msg.chat.id, text).await?;
+                            Ok(())
+                        }
+                    }
+                }),
+        )
         // text (notes)
         .branch(
             Update::filter_message()
                 .filter(|m: Message| m.text().is_some())
                 .endpoint({
                     let service = service.clone();
-                    move |bot: Bot, msg: Message| {
+                    move |bot: Bot, msg: Message, dialogue: BotDialogue| {
                         let service = service.clone();
                         async move {
                             let Some(from) = msg.from() else { return Ok(()); };
                             let text = msg.text().unwrap();
-                            let mut user = service.load(from.id.0);
 
-                            if let InputMode::AddNote = user.input {
+                            if let BotState::AddingNote = dialogue_state(&dialogue).await {
+                                let mut user = service.load(from.id.0);
                                 user.notes.insert(0, text.to_string());
                                 UserService::log(&mut user, "📝 Создана заметка");
-                                user.input = InputMode::None;
                                 service.save(from.id.0, &user);
+                                let _ = dialogue.update(BotState::MainMenu).await;
 
                                 bot.send_message(msg.chat.id, "✅ Заметка сохранена")
                                     .reply_markup(main_menu())
@@ -309,14 +958,15 @@ async fn main() {
         .branch(
             Update::filter_callback_query().endpoint({
                 let service = service.clone();
-                move |bot: Bot, q: CallbackQuery| {
+                move |bot: Bot, q: CallbackQuery, dialogue: BotDialogue| {
                     let service = service.clone();
-                    async move { handle_callback(bot, q, service).await }
+                    async move { handle_callback(bot, q, service, dialogue).await }
                 }
             }),
         );
 
     Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![storage])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -331,6 +981,7 @@ async fn handle_callback(
     bot: Bot,
     q: CallbackQuery,
     service: Arc<UserService>,
+    dialogue: BotDialogue,
 ) -> ResponseResult<()> {
     let Some(data) = q.data.as_deref() else { return Ok(()) };
     let Some(msg) = q.message.as_ref() else { return Ok(()) };
@@ -340,6 +991,8 @@ async fn handle_callback(
     let msg_id = msg.id();
 
     let mut user = service.load(user_id);
+    let state = dialogue_state(&dialogue).await;
+    let custom_quests = user.custom_quests.clone();
 
     let (text, kb) = match data {
         "profile" => (
@@ -352,19 +1005,21 @@ async fn handle_callback(
             ),
             main_menu(),
         ),
-        "quests" => ("📜 Выбери квест".into(), quest_menu()),
+        "quests" => ("📜 Выбери квест".into(), quest_menu(&custom_quests)),
         "log" => (format!("📖 Журнал\n\n{}", user.log.join("\n")), main_menu()),
         "notes" => (format!("🗒 Заметки\n\n{}", user.notes.join("\n")), notes_menu()),
         "add_note" => {
-            user.input = InputMode::AddNote;
+            let _ = dialogue.update(BotState::AddingNote).await;
             ("✍️ Напиши текст заметки".into(), InlineKeyboardMarkup::default())
         }
-        "doing" => {
-            user.awaiting_ping = false;
+        "doing" if matches!(state, BotState::AwaitingReminderReply) => {
+            let _ = dialogue.update(BotState::MainMenu).await;
             ("👍 Отлично, продолжай".into(), main_menu())
         }
-        "nothing" => {
-            user.awaiting_ping = false;
+        "nothing" if matches!(state, BotState::AwaitingReminderReply) => {
+            let deadline = Utc::now().timestamp() + 60;
+            let _ = dialogue.update(BotState::ForcedApply { deadline }).await;
+
             let bot = bot.clone();
             tokio::spawn(async move {
                 tokio::time::sleep(Duration::from_secs(60)).await;
@@ -375,15 +1030,29 @@ async fn handle_callback(
             });
             ("⚠️ Сделай один отклик прямо сейчас".into(), InlineKeyboardMarkup::default())
         }
-        "forced_done" => {
+        "forced_done" if matches!(state, BotState::ForcedApply { .. }) => {
             UserService::complete_quest(&mut user, "Отклик", 20, 1);
+            let _ = dialogue.update(BotState::MainMenu).await;
             ("✅ Засчитано".into(), main_menu())
         }
-        "q_apply" => quest(&mut user, "Отклик", 20, 1),
-        "q_study" => quest(&mut user, "Учёба", 15, 0),
-        "q_resume" => quest(&mut user, "Резюме", 30, 0),
-        "q_recruiter" => quest(&mut user, "Рекрутер", 25, 1),
-        "q_project" => quest(&mut user, "Проект", 50, 0),
+        "q_apply" => quest(&mut user, "Отклик", 20, 1, &custom_quests),
+        "q_study" => quest(&mut user, "Учёба", 15, 0, &custom_quests),
+        "q_resume" => quest(&mut user, "Резюме", 30, 0, &custom_quests),
+        "q_recruiter" => quest(&mut user, "Рекрутер", 25, 1, &custom_quests),
+        "q_project" => quest(&mut user, "Проект", 50, 0, &custom_quests),
+        data if data.starts_with("cq:") => {
+            let picked = data
+                .trim_start_matches("cq:")
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| custom_quests.get(i).cloned());
+
+            match picked {
+                Some(q) => quest(&mut user, &q.name, q.xp, q.gold, &custom_quests),
+                None => ("Квест не найден".into(), quest_menu(&custom_quests)),
+            }
+        }
+        "top" => (leaderboard_text(service.repo.as_ref(), user_id), main_menu()),
         "back" => ("Главное меню".into(), main_menu()),
         _ => return Ok(()),
     };
@@ -398,7 +1067,45 @@ async fn handle_callback(
     Ok(())
 }
 
-fn quest(user: &mut User, name: &str, xp: u32, gold: u32) -> (String, InlineKeyboardMarkup) {
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Renders the top `LEADERBOARD_SIZE` users plus the requesting user's own
+/// rank, e.g. "Ты на 7 месте из 42". Goes through `UserStore::top_n`/
+/// `count`/`rank_of` instead of `all()`, so on the SQLite backend this is
+/// an `ORDER BY ... LIMIT` plus a couple of `COUNT(*)` queries rather than
+/// a full-table scan and an in-memory sort.
+fn leaderboard_text(repo: &dyn UserStore, user_id: u64) -> String {
+    let total = repo.count();
+    let ranked = UserService::rank_users(repo.top_n(LEADERBOARD_SIZE));
+
+    let mut lines: Vec<String> = ranked
+        .iter()
+        .map(|r| {
+            let name = if r.first_name.is_empty() {
+                "Игрок"
+            } else {
+                &r.first_name
+            };
+            format!("{}. {} — ур. {} ({} XP)", r.rank, name, r.level, r.xp)
+        })
+        .collect();
+
+    if let Some(me) = ranked.iter().find(|r| r.user_id == user_id) {
+        lines.push(format!("\nТы на {} месте из {}", me.rank, total));
+    } else if let Some((rank, _)) = repo.rank_of(user_id) {
+        lines.push(format!("\nТы на {} месте из {}", rank, total));
+    }
+
+    format!("🏆 Рейтинг\n\n{}", lines.join("\n"))
+}
+
+fn quest(
+    user: &mut User,
+    name: &str,
+    xp: u32,
+    gold: u32,
+    custom_quests: &[Quest],
+) -> (String, InlineKeyboardMarkup) {
     let lvl = UserService::complete_quest(user, name, xp, gold);
 
     let mut text = format!("✅ {}\n+{} XP", name, xp);
@@ -409,5 +1116,86 @@ fn quest(user: &mut User, name: &str, xp: u32, gold: u32) -> (String, InlineKeyb
         text.push_str(&format!("\n🆙 Новый уровень {}", l));
     }
 
-    (text, quest_menu())
+    (text, quest_menu(custom_quests))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_secs_parses_single_units() {
+        assert_eq!(parse_duration_secs("45m"), Ok(45 * 60));
+        assert_eq!(parse_duration_secs("2h"), Ok(2 * 3_600));
+        assert_eq!(parse_duration_secs("1d"), Ok(86_400));
+        assert_eq!(parse_duration_secs("30s"), Ok(30));
+    }
+
+    #[test]
+    fn parse_duration_secs_combines_units_left_to_right() {
+        assert_eq!(parse_duration_secs("1h30m"), Ok(3_600 + 30 * 60));
+        assert_eq!(parse_duration_secs("2d3h"), Ok(2 * 86_400 + 3 * 3_600));
+    }
+
+    #[test]
+    fn parse_duration_secs_treats_bare_number_as_minutes() {
+        assert_eq!(parse_duration_secs("45"), Ok(45 * 60));
+    }
+
+    #[test]
+    fn parse_duration_secs_rejects_empty_unknown_unit_and_zero() {
+        assert!(parse_duration_secs("").is_err());
+        assert!(parse_duration_secs("10x").is_err());
+        assert!(parse_duration_secs("0m").is_err());
+    }
+
+    #[test]
+    fn parse_duration_secs_rejects_trailing_digits_without_a_unit() {
+        assert!(parse_duration_secs("1h30").is_err());
+    }
+
+    #[test]
+    fn parse_duration_secs_saturates_instead_of_overflowing() {
+        assert_eq!(parse_duration_secs("99999999999999999999d"), Ok(u64::MAX));
+    }
+
+    fn user(level: u32, xp: u32) -> User {
+        User {
+            level,
+            xp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rank_users_orders_by_level_then_xp_descending() {
+        let ranked = UserService::rank_users(vec![
+            (1, user(2, 10)),
+            (2, user(5, 0)),
+            (3, user(5, 50)),
+        ]);
+
+        let order: Vec<u64> = ranked.iter().map(|r| r.user_id).collect();
+        assert_eq!(order, vec![3, 2, 1]);
+        assert_eq!(
+            ranked.iter().map(|r| r.rank).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn rank_users_breaks_ties_by_ascending_user_id() {
+        let ranked = UserService::rank_users(vec![
+            (20, user(3, 10)),
+            (10, user(3, 10)),
+            (30, user(3, 10)),
+        ]);
+
+        let order: Vec<u64> = ranked.iter().map(|r| r.user_id).collect();
+        assert_eq!(order, vec![10, 20, 30]);
+        assert_eq!(
+            ranked.iter().map(|r| r.rank).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
 }